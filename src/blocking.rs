@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use futures::sync::oneshot;
+use futures::Future;
+
+/// The error yielded by the future returned from `Handle::spawn_blocking`.
+#[derive(Debug)]
+pub struct BlockingError {
+    kind: BlockingErrorKind,
+}
+
+#[derive(Debug)]
+enum BlockingErrorKind {
+    Panicked,
+    Canceled,
+}
+
+impl fmt::Display for BlockingError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            BlockingErrorKind::Panicked => write!(fmt, "blocking task panicked"),
+            BlockingErrorKind::Canceled => write!(fmt, "blocking task was canceled"),
+        }
+    }
+}
+
+impl Error for BlockingError {}
+
+/// A single unit of blocking work, type-erased so it can sit in a queue
+/// shared by every worker thread regardless of the closure's own types.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A pool of up to `max_threads` worker threads dedicated to running
+/// blocking (synchronous) work offloaded from the single-threaded runtime.
+///
+/// Jobs are queued on a channel and pulled off by whichever worker thread is
+/// free next. Worker threads are spawned lazily, one per `spawn` call until
+/// `max_threads` have been spawned, whether or not the existing workers are
+/// busy; a runtime that never calls `spawn_blocking` spawns none at all, and
+/// a `Builder::new().build()` does not provision the full cap of threads up
+/// front.
+pub(crate) struct BlockingPool {
+    jobs: mpsc::Sender<Job>,
+    jobs_rx: Arc<Mutex<mpsc::Receiver<Job>>>,
+    threads_spawned: AtomicUsize,
+    max_threads: usize,
+    thread_name: String,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(max_threads: usize, thread_name: String) -> Self {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<Job>();
+
+        BlockingPool {
+            jobs: jobs_tx,
+            jobs_rx: Arc::new(Mutex::new(jobs_rx)),
+            threads_spawned: AtomicUsize::new(0),
+            max_threads,
+            thread_name,
+        }
+    }
+
+    /// Spawn one more worker thread, unless the pool is already at capacity.
+    fn spawn_worker(&self) {
+        loop {
+            let spawned = self.threads_spawned.load(Ordering::SeqCst);
+            if spawned >= self.max_threads {
+                return;
+            }
+
+            let prev = self.threads_spawned.compare_and_swap(spawned, spawned + 1, Ordering::SeqCst);
+            if prev != spawned {
+                // Lost a race with another caller also growing the pool; retry.
+                continue;
+            }
+
+            let jobs_rx = self.jobs_rx.clone();
+            let name = format!("{}-{}", self.thread_name, spawned);
+
+            thread::Builder::new()
+                .name(name)
+                .spawn(move || loop {
+                    let job = jobs_rx.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        // The pool (and its `Sender`) has been dropped; exit.
+                        Err(_) => break,
+                    }
+                })
+                .expect("failed to spawn blocking pool thread");
+
+            return;
+        }
+    }
+
+    pub(crate) fn spawn<F, R>(&self, f: F) -> impl Future<Item = R, Error = BlockingError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            let _ = result_tx.send(result);
+        });
+
+        // Grow the pool on demand rather than up front: every job queues up
+        // regardless, but as long as we're still below `max_threads` we also
+        // bring another worker online to help drain the queue.
+        self.spawn_worker();
+
+        // `send` only fails once the receiving end is gone, and the pool keeps
+        // its own `jobs_rx` alive, so it does not fail while the pool exists.
+        // Should it fail, the job and its `result_tx` are dropped here, which
+        // surfaces as a `Canceled` error below instead of a panic.
+        let _ = self.jobs.send(job);
+
+        result_rx.then(|result| match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(_)) => Err(BlockingError {
+                kind: BlockingErrorKind::Panicked,
+            }),
+            Err(_canceled) => Err(BlockingError {
+                kind: BlockingErrorKind::Canceled,
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn bounds_concurrency_to_max_threads() {
+        let pool = BlockingPool::new(2, "test-blocking".to_string());
+
+        let concurrent = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let results: Vec<_> = (0..6)
+            .map(|_| {
+                let concurrent = concurrent.clone();
+                let peak = peak.clone();
+                pool.spawn(move || {
+                    let now = concurrent.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    concurrent.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for result in results {
+            result.wait().unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn propagates_panics_as_errors() {
+        let pool = BlockingPool::new(1, "test-blocking".to_string());
+
+        let result = pool.spawn(|| panic!("boom")).wait();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn does_not_spawn_any_thread_until_the_first_job() {
+        let pool = BlockingPool::new(100, "test-blocking".to_string());
+
+        assert_eq!(pool.threads_spawned.load(Ordering::SeqCst), 0);
+
+        pool.spawn(|| ()).wait().unwrap();
+
+        assert_eq!(pool.threads_spawned.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,76 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_timer::clock::Now;
+
+/// A virtual clock used by runtimes built with `Builder::start_paused(true)`.
+///
+/// Time only moves forward when `Runtime::advance` is called directly, or
+/// automatically when the runtime parks with no ready task and at least one
+/// pending deadline registered through `Handle::delay`/`Handle::timeout`.
+/// Time never moves backwards.
+#[derive(Clone)]
+pub(crate) struct PausedClock {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    now: Instant,
+    deadlines: BinaryHeap<Reverse<Instant>>,
+}
+
+impl PausedClock {
+    pub(crate) fn new() -> Self {
+        PausedClock {
+            inner: Arc::new(Mutex::new(Inner {
+                now: Instant::now(),
+                deadlines: BinaryHeap::new(),
+            })),
+        }
+    }
+
+    /// Move the stored instant forward by `duration`.
+    pub(crate) fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now += duration;
+    }
+
+    /// Move the stored instant forward to `deadline`, unless it is already
+    /// past that point.
+    pub(crate) fn advance_to(&self, deadline: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        if deadline > inner.now {
+            inner.now = deadline;
+        }
+    }
+
+    /// Record `deadline` so that an idle park can jump straight to it
+    /// instead of blocking.
+    pub(crate) fn register(&self, deadline: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.deadlines.push(Reverse(deadline));
+    }
+
+    /// The earliest registered deadline that is still in the future, if
+    /// any. Deadlines that have already elapsed are dropped as they are
+    /// observed.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        let mut inner = self.inner.lock().unwrap();
+        while let Some(&Reverse(deadline)) = inner.deadlines.peek() {
+            if deadline <= inner.now {
+                inner.deadlines.pop();
+            } else {
+                return Some(deadline);
+            }
+        }
+        None
+    }
+}
+
+impl Now for PausedClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+}
@@ -0,0 +1,109 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use tokio::executor::current_thread::SpawnError;
+use tokio::net::{ConnectFuture, TcpListener, TcpStream};
+use tokio_timer::{Delay, Timeout};
+
+use crate::runtime::Handle;
+
+/// Abstracts over the runtime services this crate exposes, so that code can
+/// be written once against `E: Environment` and run either on the real
+/// reactor-backed `Runtime` or against a deterministic, simulated
+/// implementation in tests.
+pub trait Environment {
+    /// The TCP stream type produced by this environment's networking.
+    type TcpStream;
+
+    /// The TCP listener type produced by this environment's networking.
+    type TcpListener;
+
+    /// The future returned by `connect`.
+    type Connect: Future<Item = Self::TcpStream, Error = io::Error>;
+
+    /// Spawn a future onto this environment.
+    fn spawn<F>(&self, future: F) -> Result<(), SpawnError>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static;
+
+    /// Returns the current instant, as seen by this environment's clock.
+    fn now(&self) -> Instant;
+
+    /// Create a `Delay` that completes at `deadline`.
+    fn delay(&self, deadline: Instant) -> Delay;
+
+    /// Wrap `future` so that it resolves to an error if it does not
+    /// complete within `duration`.
+    fn timeout<F>(&self, future: F, duration: Duration) -> Timeout<F>
+    where
+        F: Future;
+
+    /// Open a TCP connection to `addr`.
+    fn connect(&self, addr: &SocketAddr) -> Self::Connect;
+
+    /// Bind a TCP listener to `addr`.
+    fn bind(&self, addr: &SocketAddr) -> io::Result<Self::TcpListener>;
+}
+
+impl Environment for Handle {
+    type TcpStream = TcpStream;
+    type TcpListener = TcpListener;
+    type Connect = ConnectFuture;
+
+    fn spawn<F>(&self, future: F) -> Result<(), SpawnError>
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        Handle::spawn(self, future)
+    }
+
+    fn now(&self) -> Instant {
+        Handle::now(self)
+    }
+
+    fn delay(&self, deadline: Instant) -> Delay {
+        Handle::delay(self, deadline)
+    }
+
+    fn timeout<F>(&self, future: F, duration: Duration) -> Timeout<F>
+    where
+        F: Future,
+    {
+        Handle::timeout(self, future, duration)
+    }
+
+    fn connect(&self, addr: &SocketAddr) -> Self::Connect {
+        TcpStream::connect(addr)
+    }
+
+    fn bind(&self, addr: &SocketAddr) -> io::Result<Self::TcpListener> {
+        TcpListener::bind(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Future, Stream};
+
+    #[test]
+    fn connect_reaches_a_listener_bound_through_the_same_environment() {
+        let mut runtime = crate::Builder::new().build().unwrap();
+        let handle = runtime.handle();
+
+        let result = runtime.block_on(future::lazy(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let listener = Environment::bind(&handle, &addr).unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let accepted = listener.incoming().into_future().map_err(|(e, _)| e);
+            let connected = Environment::connect(&handle, &addr);
+
+            accepted.join(connected).map(|_| ())
+        }));
+
+        assert!(result.is_ok());
+    }
+}
@@ -7,10 +7,26 @@ pub use tokio::reactor::Reactor;
 use tokio_timer::clock::Clock;
 pub use tokio_timer::timer::Timer;
 
+use std::fmt;
 use std::io;
 
+mod blocking;
+mod clock;
+mod environment;
 mod runtime;
-use crate::runtime::Runtime;
+use crate::blocking::BlockingPool;
+use crate::clock::PausedClock;
+pub use crate::blocking::BlockingError;
+pub use crate::environment::Environment;
+pub use crate::runtime::{AutoAdvance, Callbacks, Handle, Runtime};
+use crate::runtime::Callback;
+use std::sync::Arc;
+
+/// The default number of threads in a runtime's blocking pool.
+const DEFAULT_MAX_BLOCKING_THREADS: usize = 100;
+
+/// The default thread name prefix used for blocking pool threads.
+const DEFAULT_BLOCKING_THREAD_NAME: &str = "current-thread-runtime-blocking";
 
 /// Builds a Single-threaded runtime with custom configuration values.
 ///
@@ -43,10 +59,35 @@ use crate::runtime::Runtime;
 /// # let _ = runtime;
 /// # }
 /// ```
-#[derive(Debug)]
 pub struct Builder {
     /// The clock to use
     clock: Clock,
+
+    /// Set when `start_paused(true)` has been called; carries the virtual
+    /// clock that `build`/`build_with_park` will install.
+    paused: Option<PausedClock>,
+
+    /// Run once, right after the runtime has been constructed.
+    after_start: Option<Callback>,
+
+    /// Run immediately before the executor parks the thread.
+    before_park: Option<Callback>,
+
+    /// Run immediately after the executor wakes from parking.
+    after_unpark: Option<Callback>,
+
+    /// The maximum number of closures that `Handle::spawn_blocking` may run
+    /// concurrently.
+    max_blocking_threads: usize,
+
+    /// The prefix used to name blocking pool threads.
+    thread_name: String,
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Builder").field("clock", &self.clock).finish()
+    }
 }
 
 impl Builder {
@@ -57,6 +98,12 @@ impl Builder {
     pub fn new() -> Builder {
         Builder {
             clock: Clock::new(),
+            paused: None,
+            after_start: None,
+            before_park: None,
+            after_unpark: None,
+            max_blocking_threads: DEFAULT_MAX_BLOCKING_THREADS,
+            thread_name: DEFAULT_BLOCKING_THREAD_NAME.to_string(),
         }
     }
 
@@ -66,26 +113,112 @@ impl Builder {
         self
     }
 
+    /// Run the built runtime's clock under manual control instead of the
+    /// system clock, for deterministic tests.
+    ///
+    /// While paused, time only moves forward when `Runtime::advance` is
+    /// called, or automatically when the runtime parks with no ready task
+    /// but a timer pending: time jumps straight to that timer's deadline
+    /// instead of blocking. This makes delay-heavy futures resolve
+    /// instantly and reproducibly.
+    ///
+    /// # Limitation
+    ///
+    /// The auto-jump-on-idle-park only knows about deadlines registered
+    /// through this crate's own `Handle::delay`/`Handle::timeout`. A
+    /// `tokio_timer::Delay` built directly with `Delay::new` is invisible to
+    /// it: parking while only such a delay is pending blocks on real
+    /// wall-clock time instead of jumping forward. Build delays through
+    /// `Handle` when running under a paused clock.
+    pub fn start_paused(&mut self, paused: bool) -> &mut Self {
+        if paused {
+            let clock = PausedClock::new();
+            self.clock = Clock::new_with_now(clock.clone());
+            self.paused = Some(clock);
+        } else {
+            self.paused = None;
+        }
+        self
+    }
+
+    /// Register a callback to run once, right after the `Runtime` has been
+    /// constructed.
+    ///
+    /// Useful for one-time setup (metrics registration, tracing spans) that
+    /// needs to happen on the runtime's thread.
+    pub fn after_start<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.after_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Register a callback to run immediately before the executor parks the
+    /// thread to wait on the reactor or timer.
+    pub fn before_park<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.before_park = Some(Arc::new(f));
+        self
+    }
+
+    /// Register a callback to run immediately after the executor wakes up
+    /// from parking.
+    pub fn after_unpark<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.after_unpark = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the maximum number of threads that `Handle::spawn_blocking` may
+    /// run concurrently.
+    ///
+    /// Defaults to 100.
+    pub fn max_blocking_threads(&mut self, max_blocking_threads: usize) -> &mut Self {
+        self.max_blocking_threads = max_blocking_threads;
+        self
+    }
+
+    /// Set the prefix used to name the runtime's blocking pool threads.
+    pub fn thread_name<S: Into<String>>(&mut self, thread_name: S) -> &mut Self {
+        self.thread_name = thread_name.into();
+        self
+    }
+
     /// Create the configured `Runtime`.
-    pub fn build(&mut self) -> io::Result<Runtime<Timer<Reactor>>> {
-        self.build_with_park(|park| park).map(|(rt, _)| rt)
+    pub fn build(&mut self) -> io::Result<Runtime<Callbacks<AutoAdvance<Timer<Reactor>>>>> {
+        let paused = self.paused.clone();
+        self.build_with_park(|timer| AutoAdvance::new(timer, paused))
+            .map(|(rt, _)| rt)
     }
 
     /// Create the configured `Runtime`.
     pub fn build_with_park<U: Park, F: FnOnce(Timer<Reactor>) -> U>(
         &mut self,
         new_park: F,
-    ) -> io::Result<(Runtime<U>, U::Unpark)> {
+    ) -> io::Result<(Runtime<Callbacks<U>>, U::Unpark)> {
         // We need a reactor to receive events about IO objects from kernel
         let reactor = Reactor::new()?;
         let reactor_handle = reactor.handle();
 
+        // `self.clock` stays owned by the `Builder` (it can be reused for
+        // another `build`/`build_with_park` call), and both the `Timer`
+        // below and the `Runtime` need their own owned `Clock`, so two
+        // clones are unavoidable here — `Timer::new_with_now` takes its
+        // `Now` implementor by value, not by reference.
+        let clock = self.clock.clone();
+
         // Place a timer wheel on top of the reactor. If there are no timeouts to fire, it'll let the
         // reactor pick up some new external events.
-        let timer = Timer::new_with_now(reactor, self.clock.clone());
+        let timer = Timer::new_with_now(reactor, clock.clone());
         let timer_handle = timer.handle();
 
         let park = new_park(timer);
+        let park = Callbacks::new(park, self.before_park.clone(), self.after_unpark.clone());
         let unpark = park.unpark();
 
         // And now put a single-threaded executor on top of the timer. When there are no futures ready
@@ -93,8 +226,71 @@ impl Builder {
         // futures to continue in their life.
         let executor = CurrentThread::new_with_park(park);
 
-        let runtime = Runtime::new2(reactor_handle, timer_handle, self.clock.clone(), executor);
+        let blocking = Arc::new(BlockingPool::new(self.max_blocking_threads, self.thread_name.clone()));
+
+        let runtime = Runtime::new4(
+            reactor_handle,
+            timer_handle,
+            clock,
+            executor,
+            self.paused.clone(),
+            blocking,
+        );
+
+        if let Some(ref f) = self.after_start {
+            f();
+        }
 
         Ok((runtime, unpark))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::sync::oneshot;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lifecycle_callbacks_fire_around_a_real_run() {
+        let after_start = Arc::new(AtomicBool::new(false));
+        let before_park = Arc::new(AtomicUsize::new(0));
+        let after_unpark = Arc::new(AtomicUsize::new(0));
+
+        let mut runtime = {
+            let after_start = after_start.clone();
+            let before_park = before_park.clone();
+            let after_unpark = after_unpark.clone();
+
+            Builder::new()
+                .after_start(move || after_start.store(true, Ordering::SeqCst))
+                .before_park(move || {
+                    before_park.fetch_add(1, Ordering::SeqCst);
+                })
+                .after_unpark(move || {
+                    after_unpark.fetch_add(1, Ordering::SeqCst);
+                })
+                .build()
+                .unwrap()
+        };
+
+        // `after_start` runs once the `Runtime` is constructed, before any
+        // task is even spawned.
+        assert!(after_start.load(Ordering::SeqCst));
+
+        // Make the executor genuinely park: nothing is ready until another
+        // thread completes the oneshot after a short real sleep.
+        let (tx, rx) = oneshot::channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let _ = tx.send(());
+        });
+
+        runtime.block_on(rx).unwrap();
+
+        assert!(before_park.load(Ordering::SeqCst) >= 1);
+        assert!(after_unpark.load(Ordering::SeqCst) >= 1);
+    }
+}